@@ -1,9 +1,15 @@
 use std::{
+    collections::VecDeque,
     fs::File,
-    io::{stdin, BufRead, BufReader, Read, Result},
+    io::{
+        stdin, stdout, BufRead, BufReader, BufWriter, Error, ErrorKind, IsTerminal, LineWriter,
+        Read, Result, Write,
+    },
+    process, thread,
+    time::Duration,
 };
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -40,29 +46,214 @@ struct Args {
     /// equivalent to -vT
     show_noprinting_and_tabs: bool,
 
+    #[arg(long, default_value_t = false)]
+    /// display $ at LF, ^M$ at CRLF and ^M at a lone CR
+    show_line_endings: bool,
+
     #[arg(short = 'A', default_value_t = false)]
     /// equivalent to -vET
     show_all: bool,
 
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    /// number the first line of each file N
+    number_start: usize,
+
+    #[arg(long, default_value_t = false)]
+    /// size the line number column to the line count of regular files
+    auto_width: bool,
+
+    #[arg(long, value_name = "N")]
+    /// output only the first N lines of each file
+    head: Option<usize>,
+
+    #[arg(long, value_name = "N")]
+    /// output only the last N lines of each file
+    tail: Option<usize>,
+
+    #[arg(long, value_name = "N")]
+    /// stop after reading N bytes of each file
+    bytes: Option<u64>,
+
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    /// process up to N files in parallel, output keeps the operand order
+    jobs: usize,
+
+    #[arg(long, default_value_t = false)]
+    /// do not report unreadable files, they still affect the exit status
+    ignore_errors: bool,
+
+    #[arg(long, value_enum, default_value_t = ColorMode::Never, value_name = "WHEN")]
+    /// colorize the ^ and $ markers
+    color: ColorMode,
+
+    #[arg(skip)]
+    use_color: bool,
+
+    #[arg(long, value_enum, value_name = "TERMINATOR")]
+    /// end every output line with TERMINATOR instead of the one read
+    output_terminator: Option<Terminator>,
+
+    #[arg(long, default_value_t = false)]
+    /// skip files with a NUL byte in their first 8 KiB, standard input is
+    /// never skipped
+    ignore_binary: bool,
+
+    #[arg(long, value_name = "N")]
+    /// cut lines after N bytes and mark them with …
+    max_line_length: Option<usize>,
+
+    #[arg(long, default_value_t = false)]
+    /// print byte, line and non-printable counts of each file to stderr
+    stats: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// print only the line, byte and character counts of each file
+    count_only: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// drop a UTF-8 byte order mark at the start of each file
+    skip_bom: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// also read the names of the files to concatenate from FILE, one per line
+    files_from: Option<String>,
+
+    #[arg(long, value_name = "FILE")]
+    /// like --files-from, but the names are separated by NUL characters
+    files_from0: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// print the offset, hex bytes and ASCII text of 16 bytes per line
+    hexdump: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// keep reading a regular file at its end and output appended lines
+    follow: bool,
+
+    #[arg(short = 'u', default_value_t = false)]
+    /// flush output after every line
+    unbuffered: bool,
+
+    #[arg(long, default_value_t = false, hide = true)]
+    /// use block buffering even if stdout is a terminal
+    block_buffered: bool,
+
     files: Vec<String>,
 }
 
+/// How long --follow waits before checking the file for new data.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Terminator {
+    Lf,
+    Crlf,
+    Nul,
+}
+
+impl Terminator {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Terminator::Lf => b"\n",
+            Terminator::Crlf => b"\r\n",
+            Terminator::Nul => b"\0",
+        }
+    }
+}
+
+/// Counters collected over the input of a file for --stats and --count-only.
+struct Stats {
+    bytes: usize,
+    lines: usize,
+    chars: usize,
+    nonprinting: usize,
+    utf8: bool,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            bytes: 0,
+            lines: 0,
+            chars: 0,
+            nonprinting: 0,
+            utf8: true,
+        }
+    }
+
+    fn add(&mut self, line: &[u8]) {
+        self.bytes += line.len();
+        // Like wc, an unterminated last line is not counted.
+        self.lines += line.ends_with(b"\n") as usize;
+        self.chars += line.iter().filter(|&&b| (b & 0xc0) != 0x80).count();
+        self.nonprinting += line
+            .iter()
+            .filter(|&&b| (b < 0x20 && b != b'\t' && b != b'\n') || b == 0x7f)
+            .count();
+        // A line never ends inside a multi-byte character.
+        self.utf8 = self.utf8 && std::str::from_utf8(line).is_ok();
+    }
+}
+
 struct CatCmd {
     arg: Args,
+    out: Box<dyn Write>,
+    failed: bool,
 }
 
 impl CatCmd {
     fn new(arg: Args) -> CatCmd {
-        CatCmd { arg }
+        let out = CatCmd::open_output(&arg);
+        CatCmd {
+            arg,
+            out,
+            failed: false,
+        }
     }
 
     fn run(&mut self) {
-        self.check_args();
-        self.process_file();
+        if let Err(err) = self.process_file() {
+            // A closed pipe is not an error worth reporting. Exit like a
+            // process killed by SIGPIPE, as echo does.
+            if err.kind() == ErrorKind::BrokenPipe {
+                process::exit(128 + libc::SIGPIPE);
+            }
+            eprintln!("cat: write error: {}", err);
+            process::exit(1);
+        }
+        if self.failed {
+            process::exit(1);
+        }
     }
 
-    fn check_args(&mut self) {
+    fn open_output(args: &Args) -> Box<dyn Write> {
+        let out = stdout();
+        if !args.block_buffered && (args.unbuffered || out.is_terminal()) {
+            Box::new(LineWriter::new(out))
+        } else {
+            Box::new(BufWriter::new(out))
+        }
+    }
+
+    /// Resolves the combined flags and reads the --files-from lists. Returns
+    /// a message if the arguments are invalid.
+    fn check_args(&mut self) -> std::result::Result<(), String> {
         let args = &mut self.arg;
+        if args.head.is_some() && args.tail.is_some() {
+            return Err(String::from("--head and --tail are mutually exclusive"));
+        }
+        if args.files_from.is_some() && args.files_from0.is_some() {
+            return Err(String::from(
+                "--files-from and --files-from0 are mutually exclusive",
+            ));
+        }
         if args.number_nonblack {
             args.number = false;
         }
@@ -74,98 +265,613 @@ impl CatCmd {
             args.show_nonprinting = true;
             args.show_tabs = true;
         }
+        if args.show_line_endings {
+            args.show_ends = true;
+        }
         if args.show_all {
             args.show_nonprinting = true;
             args.show_tabs = true;
             args.show_ends = true;
         }
+        if let Some(manifest) = &args.files_from {
+            let names = CatCmd::read_manifest(manifest, b'\n')?;
+            args.files.extend(names);
+        }
+        if let Some(manifest) = &args.files_from0 {
+            let names = CatCmd::read_manifest(manifest, b'\0')?;
+            args.files.extend(names);
+        }
+        // Operands come first, then the names from --files-from. Standard
+        // input can only be read once, so only the first `-` is kept, and
+        // none if the list itself was read from standard input.
+        let mut stdin_used = [&args.files_from, &args.files_from0]
+            .iter()
+            .any(|manifest| manifest.as_deref() == Some("-"));
+        args.files
+            .retain(|filename| filename != "-" || !std::mem::replace(&mut stdin_used, true));
+        args.use_color = match args.color {
+            ColorMode::Auto => stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        };
+        let text_flags = args.number
+            || args.number_nonblack
+            || args.show_tabs
+            || args.show_ends
+            || args.show_nonprinting
+            || args.squeeze_blank
+            || args.head.is_some()
+            || args.tail.is_some()
+            || args.max_line_length.is_some()
+            || args.output_terminator.is_some()
+            || args.auto_width
+            || args.number_start != 1
+            || !matches!(args.color, ColorMode::Never);
+        let hexdump_conflicts =
+            text_flags || args.count_only || args.follow || args.stats || args.ignore_binary;
+        if args.hexdump && hexdump_conflicts {
+            return Err(String::from(
+                "--hexdump cannot be combined with options that format or inspect text",
+            ));
+        }
+        if args.follow {
+            if args.tail.is_some() || args.count_only || args.bytes.is_some() {
+                return Err(String::from(
+                    "--follow cannot be combined with --tail, --bytes or --count-only",
+                ));
+            }
+            match args.files.as_slice() {
+                [filename] if std::fs::metadata(filename).is_ok_and(|meta| meta.is_file()) => {}
+                _ => return Err(String::from("--follow needs exactly one regular file")),
+            }
+        }
+        Ok(())
     }
 
     fn open_file(filename: &str) -> Result<Box<dyn Read>> {
         match filename {
             "-" => Ok(Box::new(stdin())),
-            _ => Ok(Box::new(File::open(&filename)?)),
+            _ => Ok(Box::new(File::open(filename)?)),
         }
     }
 
-    fn is_emptyline(line: &String) -> bool {
-        if (line.len() == 1 && line == "\n") || (line.len() == 2 && line == "\r\n") {
-            return true;
+    /// Reads the file names listed in `manifest`, separated by `sep`. Empty
+    /// names are skipped.
+    fn read_manifest(manifest: &str, sep: u8) -> std::result::Result<Vec<String>, String> {
+        let mut content = Vec::new();
+        CatCmd::open_file(manifest)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|err| format!("{}: {}", manifest, err))?;
+        Ok(content
+            .split(|b| *b == sep)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    fn count_lines(filename: &str) -> Option<usize> {
+        if filename == "-" {
+            return None;
+        }
+        let file = File::open(filename).ok()?;
+        if !file.metadata().ok()?.is_file() {
+            return None;
+        }
+
+        let mut reader = BufReader::new(file);
+        let mut count = 0;
+        let mut buff = Vec::new();
+        while let Ok(n) = reader.read_until(b'\n', &mut buff) {
+            if n == 0 {
+                break;
+            }
+            count += 1;
+            buff.clear();
         }
-        false
+        Some(count)
     }
 
-    fn process_file(&self) {
+    fn is_emptyline(line: &[u8]) -> bool {
+        line == b"\n" || line == b"\r\n"
+    }
+
+    fn process_file(&mut self) -> Result<()> {
         let args = &self.arg;
-        for idx in 0..args.files.len() {
-            let filename = &args.files[idx];
-            let input = match CatCmd::open_file(filename.as_str()) {
-                Ok(file) => file,
-                Err(err) => {
-                    println!("{}: {}", filename, err.to_string());
-                    continue;
+        let out = &mut self.out;
+        if args.count_only {
+            let mut total = Stats::new();
+            for filename in &args.files {
+                let stats = match CatCmd::count_file(args, filename) {
+                    Ok(stats) => stats,
+                    Err(err) => {
+                        CatCmd::report_error(args, filename, &err, out)?;
+                        self.failed = true;
+                        continue;
+                    }
+                };
+                writeln!(
+                    out,
+                    "{} {} {} {}",
+                    stats.lines, stats.bytes, stats.chars, filename
+                )?;
+                total.lines += stats.lines;
+                total.bytes += stats.bytes;
+                total.chars += stats.chars;
+            }
+            if args.files.len() > 1 {
+                writeln!(out, "{} {} {} total", total.lines, total.bytes, total.chars)?;
+            }
+            return out.flush();
+        }
+
+        // Followed files never end, so they cannot be buffered by a worker.
+        if args.jobs <= 1 || args.follow || args.files.iter().any(|filename| filename == "-") {
+            for filename in &args.files {
+                if let Some(err) = CatCmd::cat_file(args, filename, out)? {
+                    CatCmd::report_error(args, filename, &err, out)?;
+                    self.failed = true;
                 }
-            };
-            let mut reader = BufReader::new(input);
-
-            let mut line_num = 1;
-            let mut buff = String::new();
-            let mut has_emptyline = false;
-            while let Ok(n) = reader.read_line(&mut buff) {
-                if n == 0 {
-                    break;
+            }
+            return out.flush();
+        }
+
+        // Files are processed in batches of `jobs` so that at most that many
+        // outputs are held in memory, and written in operand order.
+        for batch in args.files.chunks(args.jobs) {
+            let results: Vec<(Vec<u8>, Option<Error>)> = thread::scope(|scope| {
+                let handles: Vec<_> = batch
+                    .iter()
+                    .map(|filename| {
+                        scope.spawn(move || {
+                            let mut buff = Vec::new();
+                            // Writing to a Vec<u8> never fails.
+                            let err = CatCmd::cat_file(args, filename, &mut buff).unwrap_or(None);
+                            (buff, err)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect()
+            });
+            // Errors are reported after the output of their file, as if the
+            // files had been processed one after another.
+            for (filename, (buff, err)) in batch.iter().zip(results) {
+                out.write_all(&buff)?;
+                if let Some(err) = err {
+                    CatCmd::report_error(args, filename, &err, out)?;
+                    self.failed = true;
                 }
+            }
+        }
+        out.flush()
+    }
 
-                let line_num_str = format!("{}", line_num);
-                let padding = " ".repeat(6 - line_num_str.len());
-                if args.number || (args.number_nonblack && !CatCmd::is_emptyline(&buff)) {
-                    print!("{}{:>}\t", padding, line_num_str);
-                    line_num += 1;
+    /// Reports that `filename` could not be read, after the output written so
+    /// far so that the message shows up in the right place.
+    fn report_error(args: &Args, filename: &str, err: &Error, out: &mut dyn Write) -> Result<()> {
+        if !args.ignore_errors {
+            out.flush()?;
+            eprintln!("cat: {}: {}", filename, err);
+        }
+        Ok(())
+    }
+
+    fn push_marker(args: &Args, out: &mut Vec<u8>, marker: &str) {
+        if args.use_color {
+            out.extend_from_slice(b"\x1b[36m");
+            out.extend_from_slice(marker.as_bytes());
+            out.extend_from_slice(b"\x1b[0m");
+        } else {
+            out.extend_from_slice(marker.as_bytes());
+        }
+    }
+
+    /// Renders a line read from the input according to the -v, -T, -E and
+    /// related options. The number column is not part of it.
+    fn transform_line(line: &[u8], args: &Args) -> Vec<u8> {
+        let mut out = Vec::with_capacity(line.len());
+        for &ch in line {
+            match ch {
+                b'\t' if args.show_tabs => CatCmd::push_marker(args, &mut out, "^I"),
+                b'\r' if args.show_line_endings => CatCmd::push_marker(args, &mut out, "^M"),
+                b'\n' => {
+                    if args.show_ends {
+                        CatCmd::push_marker(args, &mut out, "$");
+                    }
+                    out.push(ch);
+                }
+                b'\t' => out.push(ch),
+                other if args.show_nonprinting && other < 0x20 => {
+                    let marker = format!("^{}", char::from(other + 64));
+                    CatCmd::push_marker(args, &mut out, &marker);
                 }
+                other => out.push(other),
+            }
+        }
+        out
+    }
 
-                if CatCmd::is_emptyline(&buff) && args.squeeze_blank {
-                    if has_emptyline {
-                        buff.clear();
-                        continue;
+    /// Cuts the content of `line` to at most `max` bytes, at a character
+    /// boundary, and appends a marker. The line ending is kept.
+    fn truncate_line(line: &mut Vec<u8>, max: usize) {
+        let mut end = line.len();
+        if line.ends_with(b"\n") {
+            end -= 1;
+            if line[..end].ends_with(b"\r") {
+                end -= 1;
+            }
+        }
+        if end > max {
+            // Do not cut a UTF-8 character in two.
+            let mut cut = max;
+            while cut > 0 && (0x80..=0xbf).contains(&line[cut]) {
+                cut -= 1;
+            }
+            line.splice(cut..end, "…".bytes());
+        }
+    }
+
+    /// Renders `line` like transform_line, but ends it with `terminator`. A
+    /// CRLF read from the input counts as a line ending unless the CR is
+    /// shown with --show-line-endings.
+    fn replace_terminator(line: &[u8], args: &Args, terminator: Terminator) -> Vec<u8> {
+        let content = match line.strip_suffix(b"\n") {
+            Some(content) if args.show_line_endings => content,
+            Some(content) => content.strip_suffix(b"\r").unwrap_or(content),
+            // The last line of a file may not be terminated.
+            None => return CatCmd::transform_line(line, args),
+        };
+        let mut out = CatCmd::transform_line(content, args);
+        if args.show_ends {
+            CatCmd::push_marker(args, &mut out, "$");
+        }
+        out.extend_from_slice(terminator.as_bytes());
+        out
+    }
+
+    /// Opens `filename` for reading with --bytes and --skip-bom applied.
+    fn open_reader(args: &Args, filename: &str) -> Result<BufReader<Box<dyn Read>>> {
+        let input = CatCmd::open_file(filename)?;
+        let input: Box<dyn Read> = match args.bytes {
+            Some(n) => Box::new(input.take(n)),
+            None => input,
+        };
+        let mut reader = BufReader::new(input);
+        if args.skip_bom && reader.fill_buf()?.starts_with(b"\xef\xbb\xbf") {
+            reader.consume(3);
+        }
+        Ok(reader)
+    }
+
+    /// Counts the lines, bytes and characters of `filename` for --count-only.
+    fn count_file(args: &Args, filename: &str) -> Result<Stats> {
+        let mut reader = CatCmd::open_reader(args, filename)?;
+        let mut stats = Stats::new();
+        let mut buff = Vec::new();
+        loop {
+            match reader.read_until(b'\n', &mut buff) {
+                Ok(0) => return Ok(stats),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+            stats.add(&buff);
+            buff.clear();
+        }
+    }
+
+    /// Writes the content of `reader` to `out` in the format of xxd, 16 bytes
+    /// a line. Returns the error if it could not be read.
+    fn hexdump(reader: &mut dyn BufRead, out: &mut dyn Write) -> Result<Option<Error>> {
+        let mut offset = 0;
+        let mut row = Vec::with_capacity(16);
+        loop {
+            row.clear();
+            if let Err(err) = reader.take(16).read_to_end(&mut row) {
+                return Ok(Some(err));
+            }
+            if row.is_empty() {
+                return Ok(None);
+            }
+
+            let mut hex = String::with_capacity(40);
+            for (idx, b) in row.iter().enumerate() {
+                if idx > 0 && idx % 2 == 0 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{:02x}", b));
+            }
+            let text: String = row
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        char::from(b)
                     } else {
-                        has_emptyline = true;
+                        '.'
                     }
+                })
+                .collect();
+            writeln!(out, "{:08x}: {:<39}  {}", offset, hex, text)?;
+            offset += row.len();
+        }
+    }
+
+    /// Writes the transformed content of `filename` to `out`. Returns the
+    /// error if the file could not be opened or read, it is left to the
+    /// caller to report it. Errors writing to `out` are returned as Err.
+    fn cat_file(args: &Args, filename: &str, out: &mut dyn Write) -> Result<Option<Error>> {
+        let mut reader = match CatCmd::open_reader(args, filename) {
+            Ok(reader) => reader,
+            Err(err) => return Ok(Some(err)),
+        };
+        if args.hexdump {
+            return CatCmd::hexdump(&mut reader, out);
+        }
+        if args.ignore_binary && filename != "-" {
+            // The buffer of the reader holds the first 8 KiB of the file.
+            match reader.fill_buf() {
+                Ok(buff) if buff.contains(&0) => {
+                    out.flush()?;
+                    eprintln!("cat: {}: binary file skipped", filename);
+                    return Ok(None);
+                }
+                Ok(_) => {}
+                Err(err) => return Ok(Some(err)),
+            }
+        }
+
+        let mut width = 6;
+        if args.auto_width && (args.number || args.number_nonblack) {
+            if let Some(count) = CatCmd::count_lines(filename) {
+                let last = (args.number_start + count).saturating_sub(1);
+                width = last.to_string().len();
+            }
+        }
+
+        let mut line_num = args.number_start;
+        let mut buff = Vec::new();
+        let mut has_emptyline = false;
+        let mut stats = Stats::new();
+        let mut lines_read = 0;
+        // Set if the last output ended inside a line, whose rest --follow
+        // writes once it is appended.
+        let mut in_line = false;
+        let mut tail = VecDeque::new();
+        loop {
+            if args.head.is_some_and(|n| lines_read >= n) {
+                break;
+            }
+            match reader.read_until(b'\n', &mut buff) {
+                // With --follow, the end of the file means waiting for more
+                // data to be appended.
+                Ok(0) if args.follow => {
+                    out.flush()?;
+                    thread::sleep(FOLLOW_INTERVAL);
+                    continue;
+                }
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Ok(Some(err)),
+            }
+            let continued = in_line;
+            in_line = !buff.ends_with(b"\n");
+            if args.stats {
+                stats.add(&buff);
+            }
+            if continued {
+                // The rest of a line is neither blank nor numbered.
+            } else if CatCmd::is_emptyline(&buff) && args.squeeze_blank {
+                if has_emptyline {
+                    buff.clear();
+                    continue;
                 } else {
-                    has_emptyline = false;
+                    has_emptyline = true;
                 }
+            } else {
+                has_emptyline = false;
+            }
+            // Squeezed lines are not output, so they do not count for --head.
+            if !continued {
+                lines_read += 1;
+            }
 
-                for ch in buff.chars() {
-                    match ch {
-                        '\t' => {
-                            if args.show_tabs {
-                                print!("^I");
-                                continue;
-                            }
-                            print!("{}", ch);
-                        }
-                        '\n' => {
-                            if args.show_ends {
-                                print!("$");
-                            }
-                            print!("{}", ch);
-                        }
-                        other => {
-                            if args.show_nonprinting && (other as u8) < 0x20 {
-                                print!("^{}", char::from(other as u8 + 64));
-                            } else {
-                                print!("{}", other);
-                            }
-                        }
-                    }
+            if let Some(max) = args.max_line_length {
+                CatCmd::truncate_line(&mut buff, max);
+            }
+
+            // With --tail every line is rendered on its own so that only the
+            // last ones are kept, numbered by their original position.
+            let mut rendered = Vec::new();
+            let dst: &mut dyn Write = if args.tail.is_some() {
+                &mut rendered
+            } else {
+                &mut *out
+            };
+
+            if !continued && (args.number || (args.number_nonblack && !CatCmd::is_emptyline(&buff)))
+            {
+                write!(dst, "{:>width$}\t", line_num)?;
+                line_num += 1;
+            }
+
+            match args.output_terminator {
+                Some(terminator) => {
+                    dst.write_all(&CatCmd::replace_terminator(&buff, args, terminator))?
                 }
-                buff.clear();
+                None => dst.write_all(&CatCmd::transform_line(&buff, args))?,
             }
+            if args.unbuffered {
+                dst.flush()?;
+            }
+            buff.clear();
+
+            if let Some(n) = args.tail {
+                tail.push_back(rendered);
+                if tail.len() > n {
+                    tail.pop_front();
+                }
+            }
+        }
+        for rendered in tail {
+            out.write_all(&rendered)?;
+        }
+        if args.stats {
+            out.flush()?;
+            eprintln!(
+                "cat: {}: {} bytes, {} lines, {} non-printable bytes, {}",
+                filename,
+                stats.bytes,
+                stats.lines,
+                stats.nonprinting,
+                if stats.utf8 {
+                    "valid UTF-8"
+                } else {
+                    "invalid UTF-8"
+                }
+            );
         }
+        Ok(None)
     }
 }
 
 fn main() {
     let args = Args::parse();
     let mut cmd = CatCmd::new(args);
+    if let Err(msg) = cmd.check_args() {
+        eprintln!("cat: {}", msg);
+        process::exit(1);
+    }
     cmd.run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Args {
+        let mut argv = vec!["cat"];
+        argv.extend_from_slice(flags);
+        let mut cmd = CatCmd::new(Args::parse_from(argv));
+        cmd.check_args().unwrap();
+        cmd.arg
+    }
+
+    #[test]
+    fn transform_line_plain() {
+        assert_eq!(CatCmd::transform_line(b"a\tb\n", &args(&[])), b"a\tb\n");
+    }
+
+    #[test]
+    fn transform_line_show_tabs() {
+        assert_eq!(CatCmd::transform_line(b"a\tb\n", &args(&["-T"])), b"a^Ib\n");
+    }
+
+    #[test]
+    fn transform_line_show_ends() {
+        assert_eq!(CatCmd::transform_line(b"a\n", &args(&["-E"])), b"a$\n");
+        assert_eq!(CatCmd::transform_line(b"a", &args(&["-E"])), b"a");
+    }
+
+    #[test]
+    fn transform_line_show_nonprinting() {
+        assert_eq!(
+            CatCmd::transform_line(b"\x01\t\xff\n", &args(&["-v"])),
+            b"^A\t\xff\n"
+        );
+        assert_eq!(
+            CatCmd::transform_line(b"\x01\t\n", &args(&["-A"])),
+            b"^A^I$\n"
+        );
+    }
+
+    #[test]
+    fn transform_line_show_line_endings() {
+        let args = args(&["--show-line-endings"]);
+        assert_eq!(CatCmd::transform_line(b"a\r\n", &args), b"a^M$\n");
+        assert_eq!(CatCmd::transform_line(b"a\rb\n", &args), b"a^Mb$\n");
+    }
+
+    #[test]
+    fn transform_line_color() {
+        assert_eq!(
+            CatCmd::transform_line(b"a\n", &args(&["-E", "--color", "always"])),
+            b"a\x1b[36m$\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn truncate_line_keeps_short_lines() {
+        let mut line = b"abc\n".to_vec();
+        CatCmd::truncate_line(&mut line, 3);
+        assert_eq!(line, b"abc\n");
+    }
+
+    #[test]
+    fn truncate_line_keeps_line_ending() {
+        let mut line = b"abcdef\r\n".to_vec();
+        CatCmd::truncate_line(&mut line, 2);
+        assert_eq!(line, "ab…\r\n".as_bytes());
+
+        let mut line = b"abcdef".to_vec();
+        CatCmd::truncate_line(&mut line, 2);
+        assert_eq!(line, "ab…".as_bytes());
+    }
+
+    #[test]
+    fn truncate_line_at_character_boundary() {
+        let mut line = "héllo\n".as_bytes().to_vec();
+        CatCmd::truncate_line(&mut line, 2);
+        assert_eq!(line, "h…\n".as_bytes());
+
+        let mut line = "héllo\n".as_bytes().to_vec();
+        CatCmd::truncate_line(&mut line, 3);
+        assert_eq!(line, "hé…\n".as_bytes());
+    }
+
+    #[test]
+    fn replace_terminator_crlf() {
+        let args = args(&[]);
+        assert_eq!(
+            CatCmd::replace_terminator(b"a\n", &args, Terminator::Crlf),
+            b"a\r\n"
+        );
+        assert_eq!(
+            CatCmd::replace_terminator(b"a\r\n", &args, Terminator::Nul),
+            b"a\0"
+        );
+        assert_eq!(
+            CatCmd::replace_terminator(b"a", &args, Terminator::Crlf),
+            b"a"
+        );
+    }
+
+    #[test]
+    fn replace_terminator_with_markers() {
+        assert_eq!(
+            CatCmd::replace_terminator(b"a\r\n", &args(&["-E"]), Terminator::Lf),
+            b"a$\n"
+        );
+        assert_eq!(
+            CatCmd::replace_terminator(b"a\r\n", &args(&["--show-line-endings"]), Terminator::Lf),
+            b"a^M$\n"
+        );
+    }
+
+    #[test]
+    fn stats_count_like_wc() {
+        let mut stats = Stats::new();
+        stats.add("hé\n".as_bytes());
+        stats.add(b"x");
+        assert_eq!((stats.lines, stats.bytes, stats.chars), (1, 5, 4));
+    }
+
+    #[test]
+    fn emptyline() {
+        assert!(CatCmd::is_emptyline(b"\n"));
+        assert!(CatCmd::is_emptyline(b"\r\n"));
+        assert!(!CatCmd::is_emptyline(b" \n"));
+    }
+}
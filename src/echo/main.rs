@@ -1,3 +1,9 @@
+use std::{
+    env,
+    io::{self, stderr, stdin, stdout, BufWriter, IsTerminal, Read, Write},
+    process,
+};
+
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -12,108 +18,683 @@ struct Args {
     #[arg(short, required = false, default_value_t = false)]
     escape_sequence: bool,
 
-    /// strings
+    /// Print each string on its own line
+    #[arg(short = 'l', long, required = false, default_value_t = false)]
+    each_line: bool,
+
+    /// Interpret backslash escapes only from the Nth string on
+    #[arg(long, value_name = "N")]
+    escape_from: Option<usize>,
+
+    /// Print the output N times
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    repeat: usize,
+
+    /// Remove control characters other than newline and tab from the output
+    #[arg(long, required = false, default_value_t = false)]
+    strip_control: bool,
+
+    /// Warn about unterminated ANSI escape sequences in the output
+    #[arg(long, required = false, default_value_t = false)]
+    validate_ansi: bool,
+
+    /// Print each interpreted escape and the bytes it decoded to on stderr
+    #[arg(long, required = false, default_value_t = false)]
+    debug_escapes: bool,
+
+    /// Wrap lines longer than WIDTH columns at word boundaries
+    #[arg(long, value_name = "WIDTH")]
+    wrap: Option<usize>,
+
+    /// Replace $NAME and ${NAME} in the strings with environment variables,
+    /// $$ with a single $
+    #[arg(long, required = false, default_value_t = false)]
+    expand_env: bool,
+
+    /// Leave undefined variables as they are instead of removing them
+    #[arg(
+        long,
+        required = false,
+        default_value_t = false,
+        requires = "expand_env"
+    )]
+    strict: bool,
+
+    /// Use the first string as a format, %s and %d are replaced by the
+    /// following strings, and print no trailing newline
+    #[arg(
+        long,
+        required = false,
+        default_value_t = false,
+        conflicts_with_all = [
+            "no_newline",
+            "each_line",
+            "escape_from",
+            "repeat",
+            "strip_control",
+            "validate_ansi",
+            "wrap",
+        ]
+    )]
+    printf: bool,
+
+    /// Drop the bell produced by \a unless the output is a terminal
+    #[arg(long, required = false, default_value_t = false)]
+    bell_tty_only: bool,
+
+    /// Report the number of bytes written and escapes interpreted on stderr
+    #[arg(long, required = false, default_value_t = false)]
+    verbose: bool,
+
+    /// Write the output to standard error instead of standard output
+    #[arg(long, required = false, default_value_t = false)]
+    stderr: bool,
+
+    /// Append standard input, without its trailing newline, to the strings
+    #[arg(long, required = false, default_value_t = false)]
+    stdin: bool,
+
+    /// strings, everything after `--` is treated as a string
     input: Vec<String>,
 }
 
-fn convert_number(input: &mut String, base: u32, idx: usize) {
-    let sep_begin = idx + 2;
-    if sep_begin >= input.len() {
-        input.replace_range(idx..idx + 2, "");
-        return;
-    }
-    let mut sep_end = sep_begin;
+/// Decodes the digits of a numeric escape starting at `begin` and returns the
+/// encoded byte together with the number of digits consumed.
+fn convert_number(input: &[u8], base: u32, begin: usize) -> (u8, usize) {
     let num_len = match base {
         8 => 3,
-        16 => 2,
-        _ => {
-            return;
-        }
+        _ => 2,
     };
-    for i in 1..num_len {
-        match input.chars().nth(sep_begin + i) {
-            Some(ch) => {
-                if ch == '\\' {
-                    break;
+    let digits = input[begin..]
+        .iter()
+        .take(num_len)
+        .take_while(|b| char::from(**b).is_digit(base))
+        .count();
+    let n = input[begin..begin + digits]
+        .iter()
+        .fold(0, |n, b| n * base + char::from(*b).to_digit(base).unwrap());
+    (n as u8, digits)
+}
+
+/// Expands environment variables in `input`. Undefined variables expand to
+/// nothing, or are kept literally if `strict` is set.
+fn expand_env(input: &str, strict: bool) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+
+        // `len` is the length of the reference after the `$`, 0 if there is
+        // no valid one.
+        let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) if end > 0 && braced[..end].chars().all(is_name) => {
+                    (&braced[..end], end + 2)
                 }
-                sep_end = sep_begin + i;
+                _ => ("", 0),
+            }
+        } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            let end = rest.find(|c: char| !is_name(c)).unwrap_or(rest.len());
+            (&rest[..end], end)
+        } else {
+            ("", 0)
+        };
+
+        if len == 0 {
+            out.push('$');
+            continue;
+        }
+        match env::var(name) {
+            Ok(value) => out.push_str(&value),
+            Err(_) if strict => {
+                out.push('$');
+                out.push_str(&rest[..len]);
             }
-            None => {}
+            Err(_) => {}
         }
+        rest = &rest[len..];
     }
-    sep_end += 1;
+    out.push_str(rest);
+    out
+}
+
+/// An escape sequence recognized in an operand and the bytes it decoded to.
+struct Escape {
+    source: String,
+    decoded: Vec<u8>,
+}
+
+/// Appends `input` to `output` with backslash escapes interpreted, recording
+/// every recognized escape in `escapes` if given. `\a` produces nothing unless
+/// `bell` is set. Returns false if `\c` was found, which means no further
+/// output is produced.
+fn process_escapes(
+    input: &[u8],
+    posix: bool,
+    bell: bool,
+    output: &mut Vec<u8>,
+    escapes: &mut Option<Vec<Escape>>,
+) -> bool {
+    let mut idx = 0;
+    while idx < input.len() {
+        if input[idx] != b'\\' || idx + 1 == input.len() {
+            output.push(input[idx]);
+            idx += 1;
+            continue;
+        }
 
-    match u32::from_str_radix(&input[sep_begin..sep_end], base) {
-        Ok(n) => match char::from_u32(n) {
-            Some(ch) => {
-                if ch.is_ascii() {
-                    input.replace_range(idx..sep_end, &ch.to_string());
+        let begin = idx;
+        let output_len = output.len();
+        idx += 2;
+        let recognized = match input[idx - 1] {
+            b'\\' => push(output, b'\\'),
+            b'a' if bell => push(output, 0x07),
+            b'a' => true,
+            b'b' => push(output, 0x08),
+            b'c' => {
+                if let Some(escapes) = escapes {
+                    escapes.push(Escape {
+                        source: String::from("\\c"),
+                        decoded: Vec::new(),
+                    });
+                }
+                return false;
+            }
+            b'e' => push(output, 0x1b),
+            b'f' => push(output, 0x0c),
+            b'n' => push(output, b'\n'),
+            b'r' => push(output, b'\r'),
+            b't' => push(output, b'\t'),
+            b'v' => push(output, 0x0b),
+            b'x' => {
+                // `\x` without any hex digit is printed as is.
+                let (ch, digits) = convert_number(input, 16, idx);
+                idx += digits;
+                if digits == 0 {
+                    output.extend_from_slice(b"\\x");
+                    false
+                } else {
+                    push(output, ch)
                 }
             }
-            None => input.replace_range(idx..sep_end, ""),
-        },
-        Err(_) => {}
+            b'0' => {
+                let (ch, digits) = convert_number(input, 8, idx);
+                idx += digits;
+                push(output, ch)
+            }
+            // POSIX also accepts octal escapes without the leading 0.
+            b'1'..=b'7' if posix => {
+                let (ch, digits) = convert_number(input, 8, idx - 1);
+                idx += digits - 1;
+                push(output, ch)
+            }
+            other => {
+                output.push(b'\\');
+                output.push(other);
+                false
+            }
+        };
+
+        if let Some(escapes) = escapes.as_mut().filter(|_| recognized) {
+            escapes.push(Escape {
+                source: String::from_utf8_lossy(&input[begin..idx]).into_owned(),
+                decoded: output[output_len..].to_vec(),
+            });
+        }
     }
+    true
 }
 
-fn process_escapes(input: &mut String) {
-    *input = input
-        .replace("\\n", "\n")
-        .replace("\\t", "\t")
-        .replace("\\a", "\x07")
-        .replace("\\b", "\x08")
-        .replace("\\c", "\x00")
-        .replace("\\e", "\x1b")
-        .replace("\\f", "\x0c")
-        .replace("\\r", "\r")
-        .replace("\\v", "\x0b");
+/// Expands the %s, %d and %% directives of `format` with `operands` for
+/// --printf. Missing operands are empty or 0, extra ones are ignored.
+/// Returns false if an operand of %d is not a number.
+fn printf(format: &[u8], operands: &[&[u8]], output: &mut Vec<u8>) -> bool {
+    let mut ok = true;
+    let mut operands = operands.iter();
+    let mut idx = 0;
+    while idx < format.len() {
+        if format[idx] != b'%' || idx + 1 == format.len() {
+            output.push(format[idx]);
+            idx += 1;
+            continue;
+        }
+        match format[idx + 1] {
+            b's' => output.extend_from_slice(operands.next().copied().unwrap_or_default()),
+            b'd' => {
+                let operand = operands.next().copied().unwrap_or(b"0");
+                let text = String::from_utf8_lossy(operand);
+                match text.trim().parse::<i64>() {
+                    Ok(n) => output.extend_from_slice(n.to_string().as_bytes()),
+                    Err(_) => {
+                        eprintln!("echo: '{}': expected a number", text);
+                        output.push(b'0');
+                        ok = false;
+                    }
+                }
+            }
+            b'%' => output.push(b'%'),
+            other => {
+                output.push(b'%');
+                output.push(other);
+            }
+        }
+        idx += 2;
+    }
+    ok
+}
+
+fn push(output: &mut Vec<u8>, ch: u8) -> bool {
+    output.push(ch);
+    true
+}
 
-    match input.find('\x00') {
-        Some(idx) => {
-            *input = input.chars().take(idx).collect();
+fn debug_escapes(escapes: &[Escape]) {
+    for escape in escapes {
+        let decoded: Vec<String> = escape
+            .decoded
+            .iter()
+            .map(|b| format!("0x{:02x}", b))
+            .collect();
+        if escape.source == "\\c" {
+            eprintln!("echo: {} -> end of output", escape.source);
+        } else if decoded.is_empty() {
+            eprintln!("echo: {} -> nothing", escape.source);
+        } else {
+            eprintln!("echo: {} -> {}", escape.source, decoded.join(" "));
         }
-        None => {}
     }
+}
 
-    loop {
-        match input.find("\\x") {
-            Some(idx) => {
-                convert_number(input, 16, idx);
-            }
-            None => break,
+fn validate_ansi(bytes: &[u8]) {
+    let mut idx = 0;
+    while idx + 1 < bytes.len() {
+        if bytes[idx] != 0x1b || bytes[idx + 1] != b'[' {
+            idx += 1;
+            continue;
+        }
+        let begin = idx;
+        idx += 2;
+        while idx < bytes.len() && (0x20..=0x3f).contains(&bytes[idx]) {
+            idx += 1;
         }
+        // ECMA-48 final bytes, e.g. `m`, `~` or `@`.
+        if idx < bytes.len() && (0x40..=0x7e).contains(&bytes[idx]) {
+            idx += 1;
+            continue;
+        }
+        eprintln!(
+            "echo: unterminated escape sequence {:?} at byte {}",
+            String::from_utf8_lossy(&bytes[begin..idx]),
+            begin
+        );
     }
+}
+
+/// Breaks lines longer than `width` columns at spaces. Words longer than
+/// `width` are kept on a line of their own.
+fn wrap(output: &[u8], width: usize) -> Vec<u8> {
+    let columns = |s: &[u8]| s.iter().filter(|b| (**b & 0xc0) != 0x80).count();
+
+    let mut wrapped = Vec::with_capacity(output.len());
+    for (idx, line) in output.split(|b| *b == b'\n').enumerate() {
+        if idx > 0 {
+            wrapped.push(b'\n');
+        }
+        if columns(line) <= width {
+            wrapped.extend_from_slice(line);
+            continue;
+        }
 
-    loop {
-        match input.find("\\0") {
-            Some(idx) => {
-                convert_number(input, 8, idx);
+        let mut col = 0;
+        for (idx, word) in line.split(|b| *b == b' ').enumerate() {
+            let len = columns(word);
+            if idx > 0 {
+                if col > 0 && col + 1 + len > width {
+                    wrapped.push(b'\n');
+                    col = 0;
+                } else {
+                    wrapped.push(b' ');
+                    col += 1;
+                }
             }
-            None => {
-                break;
+            wrapped.extend_from_slice(word);
+            col += len;
+        }
+    }
+    wrapped
+}
+
+/// Writes `bytes` to `out`, dropping control bytes other than newline and tab
+/// if --strip-control is given.
+fn emit(args: &Args, out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    if !args.strip_control {
+        return out.write_all(bytes);
+    }
+    let stripped: Vec<u8> = bytes
+        .iter()
+        .copied()
+        .filter(|b| !(b.is_ascii_control() && *b != b'\n' && *b != b'\t'))
+        .collect();
+    out.write_all(&stripped)
+}
+
+fn bell_allowed(args: &Args) -> bool {
+    if !args.bell_tty_only {
+        return true;
+    }
+    if args.stderr {
+        stderr().is_terminal()
+    } else {
+        stdout().is_terminal()
+    }
+}
+
+/// Returns the stream the output goes to, stderr with --stderr.
+fn target(args: &Args) -> Box<dyn Write> {
+    if args.stderr {
+        Box::new(stderr().lock())
+    } else {
+        Box::new(stdout().lock())
+    }
+}
+
+/// Writes the operands to `out` one at a time, so that only the largest
+/// operand is held in memory.
+fn render(
+    args: &Args,
+    input: &[&[u8]],
+    escapes: &mut Option<Vec<Escape>>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let posix = env::var_os("POSIXLY_CORRECT").is_some();
+    let bell = bell_allowed(args);
+    let mut buff = Vec::new();
+    let input_count = input.len();
+    for (idx, s) in input.iter().enumerate() {
+        let escape = match args.escape_from {
+            Some(n) => idx + 1 >= n,
+            None => args.escape_sequence,
+        };
+        if escape {
+            buff.clear();
+            let more = process_escapes(s, posix, bell, &mut buff, escapes);
+            emit(args, out, &buff)?;
+            if !more {
+                return Ok(());
             }
+        } else {
+            emit(args, out, s)?;
+        }
+
+        if idx != input_count - 1 {
+            out.write_all(if args.each_line { b"\n" } else { b" " })?;
+        }
+    }
+
+    if !args.no_newline {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Renders the operands --repeat times. Escapes are only recorded once.
+fn render_repeated(
+    args: &Args,
+    input: &[&[u8]],
+    escapes: &mut Option<Vec<Escape>>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    for i in 0..args.repeat {
+        if i == 0 {
+            render(args, input, escapes, out)?;
+        } else {
+            render(args, input, &mut None, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Counts the bytes written through it for --verbose.
+struct CountingWriter<W: Write> {
+    inner: W,
+    bytes: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the output to its target and returns the number of bytes written.
+fn write_output(
+    args: &Args,
+    input: &[&[u8]],
+    escapes: &mut Option<Vec<Escape>>,
+) -> io::Result<usize> {
+    if args.wrap.is_none() && !args.validate_ansi {
+        let mut out = CountingWriter {
+            inner: BufWriter::new(target(args)),
+            bytes: 0,
+        };
+        render_repeated(args, input, escapes, &mut out)?;
+        out.flush()?;
+        return Ok(out.bytes);
+    }
+
+    let mut output = Vec::new();
+    render_repeated(args, input, escapes, &mut output)?;
+    if let Some(width) = args.wrap {
+        output = wrap(&output, width);
+    }
+    if args.validate_ansi {
+        validate_ansi(&output);
+    }
+    let mut out = target(args);
+    out.write_all(&output)?;
+    out.flush()?;
+    Ok(output.len())
+}
+
+/// Exits if writing the output failed. A closed pipe is not reported, the
+/// exit status is the one of a process killed by SIGPIPE.
+fn check_write<T>(result: io::Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(128 + libc::SIGPIPE),
+        Err(err) => {
+            // With --stderr the message may not be writable either.
+            let _ = writeln!(stderr(), "echo: write error: {}", err);
+            process::exit(1);
         }
     }
 }
 
 pub fn main() {
-    let mut args = Args::parse();
+    let args = Args::parse();
 
-    let input_count = args.input.len();
-    for idx in 0..(input_count) {
-        let s = &mut args.input[idx];
-        if args.escape_sequence {
-            process_escapes(s);
+    let expanded: Vec<String> = if args.expand_env {
+        args.input
+            .iter()
+            .map(|s| expand_env(s, args.strict))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let operands = if args.expand_env {
+        &expanded
+    } else {
+        &args.input
+    };
+    let mut input: Vec<&[u8]> = operands.iter().map(|s| s.as_bytes()).collect();
+    let mut data = Vec::new();
+    if args.stdin {
+        if let Err(err) = stdin().read_to_end(&mut data) {
+            eprintln!("echo: cannot read standard input: {}", err);
+            process::exit(1);
+        }
+        if data.last() == Some(&b'\n') {
+            data.pop();
         }
+        input.push(&data);
+    }
 
-        if idx == input_count - 1 {
-            print!("{}", s);
-        } else {
-            print!("{} ", s);
+    let mut escapes = if args.debug_escapes || args.verbose {
+        Some(Vec::new())
+    } else {
+        None
+    };
+
+    let mut ok = true;
+    let bytes = if args.printf {
+        let format = input.first().copied().unwrap_or_default();
+        let mut unescaped = Vec::new();
+        process_escapes(
+            format,
+            false,
+            bell_allowed(&args),
+            &mut unescaped,
+            &mut escapes,
+        );
+        let mut output = Vec::new();
+        ok = printf(&unescaped, input.get(1..).unwrap_or_default(), &mut output);
+        let mut out = target(&args);
+        check_write(out.write_all(&output).and_then(|_| out.flush()));
+        output.len()
+    } else {
+        check_write(write_output(&args, &input, &mut escapes))
+    };
+
+    if let Some(escapes) = escapes {
+        if args.debug_escapes {
+            debug_escapes(&escapes);
+        }
+        if args.verbose {
+            eprintln!(
+                "echo: wrote {} bytes, interpreted {} escape sequences",
+                bytes,
+                // Escapes are recorded for the first repetition only.
+                escapes.len() * args.repeat
+            );
         }
     }
+    if !ok {
+        process::exit(1);
+    }
+}
 
-    if !args.no_newline {
-        println!("");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(input: &str, posix: bool) -> (Vec<u8>, bool) {
+        let mut output = Vec::new();
+        let more = process_escapes(input.as_bytes(), posix, true, &mut output, &mut None);
+        (output, more)
+    }
+
+    #[test]
+    fn convert_number_digits() {
+        assert_eq!(convert_number(b"101", 8, 0), (0o101, 3));
+        assert_eq!(convert_number(b"x41z", 16, 1), (0x41, 2));
+        assert_eq!(convert_number(b"7g", 16, 0), (7, 1));
+        assert_eq!(convert_number(b"g", 16, 0), (0, 0));
+    }
+
+    #[test]
+    fn process_escapes_simple() {
+        assert_eq!(escaped("a\\tb\\n\\\\", false), (b"a\tb\n\\".to_vec(), true));
+        assert_eq!(escaped("\\q\\", false), (b"\\q\\".to_vec(), true));
+    }
+
+    #[test]
+    fn process_escapes_numeric() {
+        assert_eq!(
+            escaped("\\xc3\\xa9", false),
+            ("é".as_bytes().to_vec(), true)
+        );
+        assert_eq!(escaped("\\xc3", false), (vec![0xc3], true));
+        assert_eq!(escaped("\\xg", false), (b"\\xg".to_vec(), true));
+        assert_eq!(escaped("\\0101", false), (b"A".to_vec(), true));
+        assert_eq!(escaped("\\101", false), (b"\\101".to_vec(), true));
+        assert_eq!(escaped("\\101", true), (b"A".to_vec(), true));
+    }
+
+    #[test]
+    fn process_escapes_stop_and_nul() {
+        assert_eq!(escaped("a\\cb", false), (b"a".to_vec(), false));
+        assert_eq!(escaped("a\\0b", false), (b"a\0b".to_vec(), true));
+    }
+
+    #[test]
+    fn process_escapes_bell() {
+        let mut output = Vec::new();
+        process_escapes(b"a\\ab", false, false, &mut output, &mut None);
+        assert_eq!(output, b"ab");
+        assert_eq!(escaped("a\\ab", false).0, b"a\x07b");
+    }
+
+    #[test]
+    fn process_escapes_records() {
+        let mut output = Vec::new();
+        let mut escapes = Some(Vec::new());
+        process_escapes(b"\\x41-\\c", false, true, &mut output, &mut escapes);
+        let escapes = escapes.unwrap();
+        assert_eq!(escapes.len(), 2);
+        assert_eq!(escapes[0].source, "\\x41");
+        assert_eq!(escapes[0].decoded, b"A");
+        assert_eq!(escapes[1].source, "\\c");
+    }
+
+    #[test]
+    fn wrap_at_spaces() {
+        assert_eq!(wrap(b"aa bb cc", 5), b"aa bb\ncc");
+        assert_eq!(wrap(b"aaaaaa b", 3), b"aaaaaa\nb");
+        assert_eq!(wrap(b"ab\ncd", 1), b"ab\ncd");
+    }
+
+    #[test]
+    fn expand_env_variables() {
+        env::set_var("ECHO_TEST_VAR", "v");
+        env::remove_var("ECHO_TEST_UNSET");
+        assert_eq!(
+            expand_env("$ECHO_TEST_VAR-${ECHO_TEST_VAR}x", false),
+            "v-vx"
+        );
+        assert_eq!(expand_env("[$ECHO_TEST_UNSET]", false), "[]");
+        assert_eq!(
+            expand_env("$ECHO_TEST_UNSET ${ECHO_TEST_UNSET}", true),
+            "$ECHO_TEST_UNSET ${ECHO_TEST_UNSET}"
+        );
+        assert_eq!(expand_env("$$ $ ${ $1", false), "$ $ ${ $1");
+    }
+
+    #[test]
+    fn printf_directives() {
+        let mut output = Vec::new();
+        assert!(printf(b"%s=%d %%", &[b"key", b"5"], &mut output));
+        assert_eq!(output, b"key=5 %");
+
+        let mut output = Vec::new();
+        assert!(printf(b"[%s|%d|%q]", &[], &mut output));
+        assert_eq!(output, b"[|0|%q]");
+
+        let mut output = Vec::new();
+        assert!(!printf(b"%d", &[b"x"], &mut output));
+        assert_eq!(output, b"0");
     }
 }
@@ -1,7 +1,18 @@
-use std::{ffi::CStr, process, ptr::null_mut};
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    collections::HashMap,
+    env,
+    ffi::CStr,
+    fs, process,
+    ptr::null_mut,
+};
 
 use clap::Parser;
-use libc::{getgrgid, getpwuid, gid_t, uid_t};
+use libc::{gid_t, uid_t};
+
+mod userdb;
+
+use userdb::{FileUserDb, NssUserDb, User, UserDb};
 
 #[derive(Parser, Debug)]
 #[command(author, version, name = "id")]
@@ -30,88 +41,559 @@ struct Args {
     /// Delimit entries with NUL characters, not whitespace
     zero: bool,
 
+    #[arg(short = 'Z', long, default_value_t = false)]
+    /// Print only the security context of the process
+    context: bool,
+
+    #[arg(long)]
+    /// Delimit group IDs printed by -G with DELIMITER, -z takes precedence
+    delimiter: Option<String>,
+
+    #[arg(long, value_name = "USER", conflicts_with = "ids")]
+    /// Print information for the user named USER
+    target_user: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// Sort group IDs numerically and remove duplicates
+    sort: bool,
+
+    #[arg(long, default_value_t = false, requires = "ids")]
+    /// Print nothing, exit with 0 only if all users (groups with -g) exist
+    exists: bool,
+
+    #[arg(long, default_value_t = false, conflicts_with = "ids")]
+    /// Print the audit login user ID of the process
+    loginuid: bool,
+
+    #[arg(long, default_value_t = false, conflicts_with = "name")]
+    /// Never print names, not even in the default format
+    numeric: bool,
+
+    #[arg(long, value_name = "FORMAT", conflicts_with_all = ["user", "group", "groups"])]
+    /// Print the default format as FORMAT, with %u, %U, %g, %G and %m
+    /// replaced by the user ID and name, group ID and name, and group IDs
+    format: Option<String>,
+
+    #[arg(long, default_value_t = false, requires = "ids")]
+    /// Print the groups that only one of two users belongs to
+    diff: bool,
+
+    #[arg(long, default_value_t = false, requires = "ids")]
+    /// Print nothing, exit with 0 only if the user is a member of the group
+    /// given as second operand
+    check: bool,
+
+    #[arg(long, default_value_t = false, requires = "ids")]
+    /// Print the groups any of the users belongs to, one per line or
+    /// separated by --delimiter
+    merge: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Print euid= and egid= in the default format even if they equal the
+    /// real IDs
+    show_effective: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Mark the primary group in the group list with a `*`
+    annotate: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// Resolve users from FILE in /etc/passwd format instead of the system database
+    passwd_file: Option<String>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Resolve groups from FILE in /etc/group format instead of the system database
+    group_file: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["loginuid", "login_name", "diff", "check", "merge", "exists"]
+    )]
+    /// Print nothing, exit with 0 only if the names of the user and its
+    /// primary group can be resolved
+    quiet: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Resolve names from /etc/passwd and /etc/group only, bypassing NSS
+    local_only: bool,
+
+    #[arg(short = 'a', default_value_t = false)]
+    /// Ignored, for compatibility with other versions
+    all: bool,
+
+    #[arg(long, default_value_t = false, conflicts_with = "ids")]
+    /// Print the login name of the controlling terminal
+    login_name: bool,
+
     ids: Vec<String>,
 }
 
+/// Holds the audit login user ID, which is kept across su and sudo.
+const LOGINUID_PATH: &str = "/proc/self/loginuid";
+
+/// The local databases read with --local-only.
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+/// The user and group IDs of the process, or of a user given as operand.
+struct Identity {
+    uid: uid_t,
+    gid: gid_t,
+    euid: uid_t,
+    egid: gid_t,
+    groups: Vec<gid_t>,
+    context: Option<String>,
+}
+
+impl Identity {
+    fn current(context: Option<String>) -> Identity {
+        Identity {
+            uid: IdCmd::getuid(false),
+            gid: IdCmd::getgid(false),
+            euid: IdCmd::getuid(true),
+            egid: IdCmd::getgid(true),
+            groups: IdCmd::getgroups(),
+            context,
+        }
+    }
+
+    fn from_user(user: &User, groups: Vec<gid_t>) -> Identity {
+        Identity {
+            uid: user.uid,
+            gid: user.gid,
+            euid: user.uid,
+            egid: user.gid,
+            groups,
+            context: None,
+        }
+    }
+}
+
 struct IdCmd<'a> {
     arg: Args,
+    db: Box<dyn UserDb>,
     sep: &'a str,
+    end: &'a str,
+    groups_sep: String,
+    group_names: RefCell<HashMap<gid_t, Option<String>>>,
+    lookups: Cell<usize>,
+    resolved: Cell<usize>,
+    warned: Cell<bool>,
+    context: OnceCell<Option<String>>,
 }
 
 impl<'a> IdCmd<'a> {
     fn new(arg: Args) -> IdCmd<'a> {
         let mut sep = " ";
+        let mut end = "\n";
         if arg.zero {
             sep = "\0";
+            end = "\0";
         }
+        let groups_sep = match &arg.delimiter {
+            Some(delimiter) if !arg.zero => delimiter.clone(),
+            _ => String::from(sep),
+        };
 
-        IdCmd { arg, sep }
+        let mut passwd_file = arg.passwd_file.as_deref();
+        let mut group_file = arg.group_file.as_deref();
+        if arg.local_only {
+            passwd_file = passwd_file.or(Some(PASSWD_PATH));
+            group_file = group_file.or(Some(GROUP_PATH));
+        }
+        let db: Box<dyn UserDb> = if passwd_file.is_some() || group_file.is_some() {
+            match FileUserDb::open(passwd_file, group_file) {
+                Ok(db) => Box::new(db),
+                Err(err) => {
+                    eprintln!("id: {}", err);
+                    process::exit(1);
+                }
+            }
+        } else {
+            Box::new(NssUserDb)
+        };
+
+        IdCmd {
+            arg,
+            db,
+            sep,
+            end,
+            groups_sep,
+            group_names: RefCell::new(HashMap::new()),
+            lookups: Cell::new(0),
+            resolved: Cell::new(0),
+            warned: Cell::new(false),
+            context: OnceCell::new(),
+        }
     }
 
     fn run(&self) {
-        self.check_args();
+        let ok = if self.arg.quiet {
+            self.resolves()
+        } else {
+            let ok = self.print_targets();
+            self.warn_no_database();
+            ok
+        };
+        if !ok {
+            process::exit(1);
+        }
+    }
 
+    /// Prints the requested information for every target and returns false
+    /// if any of them could not be resolved.
+    fn print_targets(&self) -> bool {
         let args = &self.arg;
-        if args.ids.len() == 0 {
-            if args.user {
-                self.print_user_only();
-            } else if args.group {
-                self.print_group_only();
-            } else if args.groups {
-                self.print_groups_only();
-            } else {
-                self.print_user_info();
+        if args.login_name {
+            return self.print_login_name();
+        }
+
+        if args.loginuid {
+            return self.print_loginuid();
+        }
+
+        if args.diff {
+            return self.print_diff();
+        }
+
+        if args.check {
+            return self.check_membership();
+        }
+
+        if args.merge {
+            return self.print_merge();
+        }
+
+        if args.exists {
+            return args.ids.iter().all(|id| {
+                if args.group {
+                    self.lookup_group(id).is_some()
+                } else {
+                    self.lookup_user(id).is_some()
+                }
+            });
+        }
+
+        if let Some(name) = &args.target_user {
+            match self.lookup_user_by_name(name) {
+                Some(ident) => self.print_identity(&ident),
+                None => {
+                    eprintln!("id: '{}': no such user", name);
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        if args.ids.is_empty() {
+            if args.context {
+                return self.print_context_only();
+            }
+            self.print_identity(&Identity::current(self.get_context()));
+            return true;
+        }
+
+        let mut failed = false;
+        for id in &args.ids {
+            match self.lookup_user(id) {
+                Some(ident) => {
+                    self.print_identity(&ident);
+                    // Like GNU id, every group list of several users ends
+                    // with an extra NUL with -z.
+                    if args.ids.len() > 1 && args.zero && args.groups {
+                        print!("\0");
+                    }
+                }
+                None => {
+                    eprintln!("id: '{}': no such user", id);
+                    failed = true;
+                }
             }
         }
+        !failed
     }
 
-    fn check_args(&self) {
+    /// Tells whether the user and primary group names of the process, or of
+    /// every user given as operand, can be resolved, for --quiet.
+    fn resolves(&self) -> bool {
+        let resolved = |ident: &Identity| {
+            self.get_username(ident.uid).is_some() && self.get_groupname(ident.gid).is_some()
+        };
+        if let Some(name) = &self.arg.target_user {
+            return self
+                .lookup_user_by_name(name)
+                .is_some_and(|ident| resolved(&ident));
+        }
+        if self.arg.ids.is_empty() {
+            return resolved(&Identity::current(None));
+        }
+        self.arg
+            .ids
+            .iter()
+            .all(|id| self.lookup_user(id).is_some_and(|ident| resolved(&ident)))
+    }
+
+    /// Tells the user once if not a single user or group name could be
+    /// resolved, which usually means the passwd and group databases are
+    /// missing, e.g. in minimal containers.
+    fn warn_no_database(&self) {
+        if self.lookups.get() > 0 && self.resolved.get() == 0 && !self.warned.replace(true) {
+            eprintln!("id: no passwd or group database available");
+        }
+    }
+
+    /// Returns a message if the given options cannot be combined.
+    fn check_args(&self) -> Result<(), String> {
         let args = &self.arg;
-        if (args.real || args.name) && !(args.user || args.group || args.groups) {
-            println!("cannot print only names or real IDs in default format");
-            process::exit(1);
+        let only = args.user || args.group || args.groups || args.loginuid || args.merge;
+        if (args.real || args.name) && !only {
+            return Err(String::from(
+                "cannot print only names or real IDs in default format",
+            ));
+        }
+        if args.context {
+            if !args.ids.is_empty() || args.target_user.is_some() {
+                return Err(String::from(
+                    "cannot print security context when user specified",
+                ));
+            }
+            if args.user || args.group || args.groups || args.loginuid || args.login_name {
+                return Err(String::from(
+                    "cannot print \"only\" of more than one choice",
+                ));
+            }
+            if args.format.is_some() {
+                return Err(String::from(
+                    "--context (-Z) cannot be combined with --format",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn print_identity(&self, ident: &Identity) {
+        let args = &self.arg;
+        if args.user {
+            self.print_user_only(ident);
+        } else if args.group {
+            self.print_group_only(ident);
+        } else if args.groups {
+            self.print_groups_only(ident);
+        } else if let Some(format) = &args.format {
+            print!("{}{}", self.expand_format(format, ident), self.end);
+        } else {
+            self.print_user_info(ident);
+        }
+    }
+
+    /// Prints, for each of the two users given as operands, the groups the
+    /// other one is not a member of.
+    fn print_diff(&self) -> bool {
+        let ids = &self.arg.ids;
+        if ids.len() != 2 {
+            eprintln!("id: --diff needs exactly two users");
+            return false;
+        }
+        let mut idents = Vec::new();
+        for id in ids {
+            match self.lookup_user(id) {
+                Some(ident) => idents.push(ident),
+                None => {
+                    eprintln!("id: '{}': no such user", id);
+                    return false;
+                }
+            }
+        }
+
+        let groups: Vec<Vec<gid_t>> = idents.iter().map(|ident| self.get_groups(ident)).collect();
+        for (idx, id) in ids.iter().enumerate() {
+            let other = &groups[1 - idx];
+            let only: Vec<String> = groups[idx]
+                .iter()
+                .filter(|gid| !other.contains(gid))
+                .map(|gid| self.group_field(*gid))
+                .collect();
+            if only.is_empty() {
+                print!("{}:{}", id, self.end);
+            } else {
+                print!("{}: {}{}", id, only.join(","), self.end);
+            }
         }
+        true
     }
 
-    fn print_user_only(&self) {
-        let uid = IdCmd::getuid(!self.arg.real);
+    /// Tells whether the user given as first operand is a member of the group
+    /// given as second operand, either as its primary or a supplementary
+    /// group.
+    fn check_membership(&self) -> bool {
+        let (user, group) = match self.arg.ids.as_slice() {
+            [user, group] => (user, group),
+            _ => {
+                eprintln!("id: --check needs a user and a group");
+                return false;
+            }
+        };
+        let ident = match self.lookup_user(user) {
+            Some(ident) => ident,
+            None => {
+                eprintln!("id: '{}': no such user", user);
+                return false;
+            }
+        };
+        let gid = match self.lookup_group(group) {
+            Some(gid) => gid,
+            None => {
+                eprintln!("id: '{}': no such group", group);
+                return false;
+            }
+        };
+        ident.gid == gid || ident.groups.contains(&gid)
+    }
+
+    /// Prints the union of the groups of all users given as operands, in the
+    /// order they are first found, or sorted with --sort.
+    fn print_merge(&self) -> bool {
+        let mut ok = true;
+        let mut groups = Vec::new();
+        for id in &self.arg.ids {
+            match self.lookup_user(id) {
+                Some(ident) => groups.extend(self.get_groups(&ident)),
+                None => {
+                    eprintln!("id: '{}': no such user", id);
+                    ok = false;
+                }
+            }
+        }
+        if self.arg.sort {
+            groups.sort_unstable();
+        }
+        let mut seen = Vec::new();
+        groups.retain(|gid| {
+            let new = !seen.contains(gid);
+            seen.push(*gid);
+            new
+        });
+
+        let fields: Vec<String> = groups
+            .iter()
+            .map(|&gid| {
+                if self.arg.name {
+                    self.group_name(gid)
+                } else {
+                    gid.to_string()
+                }
+            })
+            .collect();
+        if self.arg.delimiter.is_some() && !self.arg.zero {
+            print!("{}{}", fields.join(&self.groups_sep), self.end);
+        } else {
+            for field in fields {
+                print!("{}{}", field, self.end);
+            }
+        }
+        ok
+    }
+
+    fn print_context_only(&self) -> bool {
+        match self.get_context() {
+            Some(context) => {
+                print!("{}{}", context, self.end);
+                true
+            }
+            None => {
+                eprintln!("id: --context (-Z) works only on an SELinux-enabled kernel");
+                false
+            }
+        }
+    }
+
+    fn print_login_name(&self) -> bool {
+        match IdCmd::get_login_name() {
+            Some(name) => {
+                print!("{}{}", name, self.end);
+                true
+            }
+            None => {
+                eprintln!("id: cannot determine login name");
+                false
+            }
+        }
+    }
+
+    fn print_loginuid(&self) -> bool {
+        let content = match fs::read_to_string(LOGINUID_PATH) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("id: cannot read {}: {}", LOGINUID_PATH, err);
+                return false;
+            }
+        };
+        let uid = match content.trim().parse::<uid_t>() {
+            Ok(uid) if uid != uid_t::MAX => uid,
+            Ok(_) => {
+                eprintln!("id: login UID is not set");
+                return false;
+            }
+            Err(_) => {
+                eprintln!("id: invalid login UID '{}'", content.trim());
+                return false;
+            }
+        };
+
+        if self.arg.name {
+            match self.get_username(uid) {
+                Some(name) => print!("{}{}", name, self.end),
+                None => {
+                    print!("(cannot find name for user ID {}){}", uid, self.end);
+                    return false;
+                }
+            }
+            return true;
+        }
+        print!("{}{}", uid, self.end);
+        true
+    }
+
+    fn print_user_only(&self, ident: &Identity) {
+        let uid = if self.arg.real { ident.uid } else { ident.euid };
         if self.arg.name {
-            match IdCmd::get_username_by_uid(uid) {
+            match self.get_username(uid) {
                 Some(name) => {
-                    println!("{}", name);
+                    print!("{}{}", name, self.end);
                 }
                 None => {
-                    println!("(cannot find name for user ID {})", uid);
+                    print!("(cannot find name for user ID {}){}", uid, self.end);
                     process::exit(1);
                 }
             }
             return;
         }
-        println!("{}", uid);
+        print!("{}{}", uid, self.end);
     }
 
-    fn print_group_only(&self) {
-        let gid = IdCmd::getgid(!self.arg.real);
+    fn print_group_only(&self, ident: &Identity) {
+        let gid = if self.arg.real { ident.gid } else { ident.egid };
         if self.arg.name {
-            match IdCmd::get_groupname_by_gid(gid) {
+            match self.get_groupname(gid) {
                 Some(name) => {
-                    println!("{}", name);
+                    print!("{}{}", name, self.end);
                 }
                 None => {
-                    println!("(cannot find name for group ID {})", gid);
+                    print!("(cannot find name for group ID {}){}", gid, self.end);
                     process::exit(1);
                 }
             }
             return;
         }
-        println!("{}", gid);
+        print!("{}{}", gid, self.end);
     }
 
-    fn print_groups_only(&self) {
-        let groups = IdCmd::getgroups();
+    fn print_groups_only(&self, ident: &Identity) {
+        let groups = &self.get_groups(ident);
         for i in 0..groups.len() {
             if self.arg.name {
-                match IdCmd::get_groupname_by_gid(groups[i]) {
+                match self.get_groupname(groups[i]) {
                     Some(name) => {
                         print!("{}", name);
                     }
@@ -123,80 +605,142 @@ impl<'a> IdCmd<'a> {
             } else {
                 print!("{}", groups[i]);
             }
+            print!("{}", self.annotation(ident, groups[i]));
             if i < groups.len() - 1 {
-                print!(" ");
+                print!("{}", self.groups_sep);
             }
         }
+        print!("{}", self.end);
     }
 
-    fn print_user_info(&self) {
-        let uid = IdCmd::getuid(false);
-        let gid = IdCmd::getgid(false);
+    fn print_user_info(&self, ident: &Identity) {
+        let uid = ident.uid;
+        let gid = ident.gid;
 
-        unsafe {
-            let username = match IdCmd::get_username_by_uid(uid) {
-                Some(name) => String::from(name),
-                None => {
-                    format!("cannot find name for user ID {}", uid)
-                }
-            };
-            print!("uid={}({}){}", uid, username.as_str(), self.sep);
+        print!("uid={}{}", self.user_field(uid), self.sep);
+        print!("gid={}", self.group_field(gid));
 
-            let group_name = match IdCmd::get_groupname_by_gid(gid) {
-                Some(name) => String::from(name),
-                None => {
-                    format!("cannot find name for group ID {}", gid)
-                }
-            };
-            print!("gid={}({})", gid, group_name);
+        let euid = ident.euid;
+        if euid != uid || self.arg.show_effective {
+            print!("{}euid={}", self.sep, self.user_field(euid));
+        }
+        let egid = ident.egid;
+        if egid != gid || self.arg.show_effective {
+            print!("{}egid={}", self.sep, self.group_field(egid));
+        }
 
-            let euid = IdCmd::getuid(true);
-            if euid != uid {
-                let ename = match IdCmd::get_username_by_uid(euid) {
-                    Some(name) => String::from(name),
-                    None => {
-                        format!("cannot find name for user ID {}", euid)
-                    }
-                };
-                print!("{}euid={}({})", self.sep, euid, ename);
-            }
-            let egid = IdCmd::getgid(true);
-            if egid != gid {
-                let ename = match IdCmd::get_groupname_by_gid(egid) {
-                    Some(name) => String::from(name),
-                    None => {
-                        format!("cannot find name for user ID {}", egid)
-                    }
-                };
-                print!("{}egid={}({})", self.sep, egid, ename);
+        let groups = &self.get_groups(ident);
+        if !groups.is_empty() {
+            print!("{}groups=", self.sep);
+        }
+        for i in 0..groups.len() {
+            print!("{}", self.group_field(groups[i]));
+            print!("{}", self.annotation(ident, groups[i]));
+            if i != groups.len() - 1 {
+                print!(",");
             }
+        }
 
-            let groups_num = libc::getgroups(0, null_mut::<u32>());
-            if groups_num <= 0 {
-                return;
-            }
+        if let Some(context) = &ident.context {
+            print!("{}context={}", self.sep, context);
+        }
+        print!("{}", self.end);
+    }
 
-            let mut groups = vec![0 as gid_t; groups_num as usize];
-            if libc::getgroups(groups_num, groups.as_mut_ptr()) == -1 {
-                println!("failed to get groups");
-                return;
-            }
-            print!("{}groups=", self.sep);
-            for i in 0..groups.len() {
-                print!("{}", groups[i]);
+    /// Returns the --annotate marker for `gid` in the group list of `ident`.
+    fn annotation(&self, ident: &Identity, gid: gid_t) -> &str {
+        if self.arg.annotate && gid == ident.gid {
+            "*"
+        } else {
+            ""
+        }
+    }
 
-                let group_name = match IdCmd::get_groupname_by_gid(groups[i]) {
-                    Some(name) => String::from(name),
-                    None => {
-                        format!("cannot find name for group ID {}", groups[i])
-                    }
-                };
-                print!("({})", group_name);
-                if i != groups.len() - 1 {
-                    print!(",");
+    /// Replaces the --format tokens in `format`. Unknown tokens are kept as
+    /// they are, `%%` is a literal `%`.
+    fn expand_format(&self, format: &str, ident: &Identity) -> String {
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                out.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('u') => out.push_str(&ident.uid.to_string()),
+                Some('U') => out.push_str(&self.user_name(ident.uid)),
+                Some('g') => out.push_str(&ident.gid.to_string()),
+                Some('G') => out.push_str(&self.group_name(ident.gid)),
+                Some('m') => {
+                    let groups: Vec<String> = self
+                        .get_groups(ident)
+                        .iter()
+                        .map(|gid| gid.to_string())
+                        .collect();
+                    out.push_str(&groups.join(","));
                 }
+                Some('%') => out.push('%'),
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
             }
         }
+        out
+    }
+
+    /// Returns the name of `uid`, or the number if it has none or with
+    /// --numeric.
+    fn user_name(&self, uid: uid_t) -> String {
+        if self.arg.numeric {
+            return uid.to_string();
+        }
+        self.get_username(uid).unwrap_or_else(|| uid.to_string())
+    }
+
+    fn group_name(&self, gid: gid_t) -> String {
+        if self.arg.numeric {
+            return gid.to_string();
+        }
+        self.get_groupname(gid).unwrap_or_else(|| gid.to_string())
+    }
+
+    /// Formats a user ID as `uid(name)` for the default format, or just the
+    /// number with --numeric.
+    fn user_field(&self, uid: uid_t) -> String {
+        if self.arg.numeric {
+            return uid.to_string();
+        }
+        match self.get_username(uid) {
+            Some(name) => format!("{}({})", uid, name),
+            // Names missing from the local files may still exist in NSS.
+            None if self.arg.local_only => uid.to_string(),
+            None => format!("{}(cannot find name for user ID {})", uid, uid),
+        }
+    }
+
+    fn group_field(&self, gid: gid_t) -> String {
+        if self.arg.numeric {
+            return gid.to_string();
+        }
+        match self.get_groupname(gid) {
+            Some(name) => format!("{}({})", gid, name),
+            // Names missing from the local files may still exist in NSS.
+            None if self.arg.local_only => gid.to_string(),
+            None => format!("{}(cannot find name for group ID {})", gid, gid),
+        }
+    }
+
+    /// Returns the groups of `ident`, sorted numerically without duplicates
+    /// if --sort is given.
+    fn get_groups(&self, ident: &Identity) -> Vec<gid_t> {
+        let mut groups = ident.groups.clone();
+        if self.arg.sort {
+            groups.sort_unstable();
+            groups.dedup();
+        }
+        groups
     }
 
     fn getuid(effective: bool) -> uid_t {
@@ -219,50 +763,125 @@ impl<'a> IdCmd<'a> {
         }
     }
 
+    /// Returns the supplementary groups of the process. Like GNU id, the
+    /// effective group comes first if it is not one of them.
     fn getgroups() -> Vec<gid_t> {
+        let egid = IdCmd::getgid(true);
         let groups_num;
         unsafe {
             groups_num = libc::getgroups(0, null_mut::<u32>());
             if groups_num <= 0 {
-                return vec![0];
+                return vec![egid];
             }
         }
 
         let mut groups = vec![0 as gid_t; groups_num as usize];
         unsafe {
             if libc::getgroups(groups_num, groups.as_mut_ptr()) == -1 {
-                return vec![0];
+                return vec![egid];
             }
         }
+        if !groups.contains(&egid) {
+            groups.insert(0, egid);
+        }
         groups
     }
 
-    fn get_username_by_uid(uid: uid_t) -> Option<&'static str> {
-        unsafe {
-            let entry = getpwuid(uid);
-            if !entry.is_null() {
-                let username = CStr::from_ptr((*entry).pw_name).to_str().unwrap();
-                return Some(username);
-            } else {
-                return None;
-            }
+    fn identity_of(&self, user: &User) -> Identity {
+        Identity::from_user(user, self.db.group_list(user))
+    }
+
+    fn lookup_user_by_name(&self, name: &str) -> Option<Identity> {
+        let user = self.db.user_by_name(name)?;
+        Some(self.identity_of(&user))
+    }
+
+    /// Resolves an operand as a user name first, then as a numeric user ID.
+    fn lookup_user(&self, id: &str) -> Option<Identity> {
+        let user = self
+            .db
+            .user_by_name(id)
+            .or_else(|| self.db.user_by_uid(id.parse().ok()?))?;
+        Some(self.identity_of(&user))
+    }
+
+    /// Resolves an operand as a group name first, then as a numeric group ID.
+    fn lookup_group(&self, id: &str) -> Option<gid_t> {
+        let group = self
+            .db
+            .group_by_name(id)
+            .or_else(|| self.db.group_by_gid(id.parse().ok()?))?;
+        Some(group.gid)
+    }
+
+    /// Looks up the name of a group, remembering the result so that groups
+    /// shared by several users are only resolved once.
+    fn get_groupname(&self, gid: gid_t) -> Option<String> {
+        self.group_names
+            .borrow_mut()
+            .entry(gid)
+            .or_insert_with(|| self.count_lookup(self.db.group_by_gid(gid).map(|group| group.name)))
+            .clone()
+    }
+
+    fn get_username(&self, uid: uid_t) -> Option<String> {
+        self.count_lookup(self.db.user_by_uid(uid).map(|user| user.name))
+    }
+
+    fn count_lookup(&self, name: Option<String>) -> Option<String> {
+        self.lookups.set(self.lookups.get() + 1);
+        if name.is_some() {
+            self.resolved.set(self.resolved.get() + 1);
         }
+        name
     }
 
-    fn get_groupname_by_gid(gid: gid_t) -> Option<&'static str> {
+    /// Returns the name of the user logged in on the controlling terminal,
+    /// falling back to $LOGNAME when there is no such terminal.
+    fn get_login_name() -> Option<String> {
         unsafe {
-            let entry = getgrgid(gid);
-            if !entry.is_null() {
-                let groupname = CStr::from_ptr((*entry).gr_name).to_str().unwrap();
-                return Some(groupname);
+            let name = libc::getlogin();
+            if !name.is_null() {
+                return Some(CStr::from_ptr(name).to_string_lossy().into_owned());
             }
+        }
+        env::var("LOGNAME").ok().filter(|name| !name.is_empty())
+    }
+
+    /// Returns the security context of the process, which is only read once.
+    fn get_context(&self) -> Option<String> {
+        self.context.get_or_init(IdCmd::read_context).clone()
+    }
+
+    #[cfg(feature = "selinux")]
+    fn selinux_enabled() -> bool {
+        std::path::Path::new("/sys/fs/selinux/enforce").exists()
+    }
+
+    #[cfg(not(feature = "selinux"))]
+    fn selinux_enabled() -> bool {
+        false
+    }
+
+    fn read_context() -> Option<String> {
+        if !IdCmd::selinux_enabled() {
             return None;
         }
+        let content = fs::read_to_string("/proc/self/attr/current").ok()?;
+        let context = content.trim_end_matches(['\0', '\n']);
+        if context.is_empty() {
+            return None;
+        }
+        Some(String::from(context))
     }
 }
 
 pub fn main() {
     let args = Args::parse();
     let cmd = IdCmd::new(args);
+    if let Err(msg) = cmd.check_args() {
+        eprintln!("id: {}", msg);
+        process::exit(1);
+    }
     cmd.run();
 }
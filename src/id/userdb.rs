@@ -0,0 +1,306 @@
+use std::{
+    ffi::{CStr, CString},
+    fs,
+    io::{Error, Result},
+};
+
+use libc::{c_int, getgrgid, getgrnam, getpwnam, getpwuid, gid_t, group, passwd, uid_t};
+
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+#[derive(Clone)]
+pub struct Group {
+    pub name: String,
+    pub gid: gid_t,
+    pub members: Vec<String>,
+}
+
+/// Source of user and group entries.
+pub trait UserDb {
+    fn user_by_name(&self, name: &str) -> Option<User>;
+    fn user_by_uid(&self, uid: uid_t) -> Option<User>;
+    fn group_by_name(&self, name: &str) -> Option<Group>;
+    fn group_by_gid(&self, gid: gid_t) -> Option<Group>;
+    /// Returns the primary group of `user` followed by its supplementary
+    /// groups.
+    fn group_list(&self, user: &User) -> Vec<gid_t>;
+}
+
+/// Resolves users and groups through libc, i.e. whatever NSS is configured.
+pub struct NssUserDb;
+
+impl NssUserDb {
+    fn to_user(entry: *const passwd) -> Option<User> {
+        if entry.is_null() {
+            return None;
+        }
+        unsafe {
+            Some(User {
+                name: CStr::from_ptr((*entry).pw_name)
+                    .to_string_lossy()
+                    .into_owned(),
+                uid: (*entry).pw_uid,
+                gid: (*entry).pw_gid,
+            })
+        }
+    }
+
+    fn to_group(entry: *const group) -> Option<Group> {
+        if entry.is_null() {
+            return None;
+        }
+        unsafe {
+            let mut members = Vec::new();
+            let mut member = (*entry).gr_mem;
+            while !member.is_null() && !(*member).is_null() {
+                members.push(CStr::from_ptr(*member).to_string_lossy().into_owned());
+                member = member.add(1);
+            }
+            Some(Group {
+                name: CStr::from_ptr((*entry).gr_name)
+                    .to_string_lossy()
+                    .into_owned(),
+                gid: (*entry).gr_gid,
+                members,
+            })
+        }
+    }
+}
+
+impl UserDb for NssUserDb {
+    fn user_by_name(&self, name: &str) -> Option<User> {
+        let name = CString::new(name).ok()?;
+        NssUserDb::to_user(unsafe { getpwnam(name.as_ptr()) })
+    }
+
+    fn user_by_uid(&self, uid: uid_t) -> Option<User> {
+        NssUserDb::to_user(unsafe { getpwuid(uid) })
+    }
+
+    fn group_by_name(&self, name: &str) -> Option<Group> {
+        let name = CString::new(name).ok()?;
+        NssUserDb::to_group(unsafe { getgrnam(name.as_ptr()) })
+    }
+
+    fn group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        NssUserDb::to_group(unsafe { getgrgid(gid) })
+    }
+
+    fn group_list(&self, user: &User) -> Vec<gid_t> {
+        let name = match CString::new(user.name.as_str()) {
+            Ok(name) => name,
+            Err(_) => return vec![user.gid],
+        };
+        let mut groups_num: c_int = 16;
+        loop {
+            let mut groups = vec![0 as gid_t; groups_num as usize];
+            let ret = unsafe {
+                libc::getgrouplist(
+                    name.as_ptr(),
+                    user.gid,
+                    groups.as_mut_ptr(),
+                    &mut groups_num,
+                )
+            };
+            if ret >= 0 {
+                groups.truncate(groups_num as usize);
+                return groups;
+            }
+            // groups_num now holds the number of groups the user belongs to.
+        }
+    }
+}
+
+/// Resolves users and groups from files in the /etc/passwd and /etc/group
+/// format, e.g. those of another root file system. Whichever of the two
+/// files is not given is looked up through NSS.
+pub struct FileUserDb {
+    users: Option<Vec<User>>,
+    groups: Option<Vec<Group>>,
+}
+
+impl FileUserDb {
+    pub fn open(passwd_file: Option<&str>, group_file: Option<&str>) -> Result<FileUserDb> {
+        let users = match passwd_file {
+            Some(path) => Some(FileUserDb::parse_passwd(&FileUserDb::read(path)?)),
+            None => None,
+        };
+        let groups = match group_file {
+            Some(path) => Some(FileUserDb::parse_group(&FileUserDb::read(path)?)),
+            None => None,
+        };
+        Ok(FileUserDb { users, groups })
+    }
+
+    fn read(path: &str) -> Result<String> {
+        fs::read_to_string(path).map_err(|err| Error::new(err.kind(), format!("{}: {}", path, err)))
+    }
+
+    /// Parses `name:password:uid:gid:gecos:home:shell` lines. Trailing fields
+    /// may be missing, lines without a valid uid and gid are skipped.
+    fn parse_passwd(content: &str) -> Vec<User> {
+        content
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                Some(User {
+                    name: String::from(*fields.first().filter(|name| !name.is_empty())?),
+                    uid: fields.get(2)?.parse().ok()?,
+                    gid: fields.get(3)?.parse().ok()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Parses `name:password:gid:member,...` lines.
+    fn parse_group(content: &str) -> Vec<Group> {
+        content
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                let members = fields
+                    .get(3)
+                    .map(|members| {
+                        members
+                            .split(',')
+                            .filter(|member| !member.is_empty())
+                            .map(String::from)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Some(Group {
+                    name: String::from(*fields.first().filter(|name| !name.is_empty())?),
+                    gid: fields.get(2)?.parse().ok()?,
+                    members,
+                })
+            })
+            .collect()
+    }
+
+    fn find_user(&self, pred: impl Fn(&User) -> bool) -> Option<User> {
+        self.users.as_ref()?.iter().find(|user| pred(user)).cloned()
+    }
+
+    fn find_group(&self, pred: impl Fn(&Group) -> bool) -> Option<Group> {
+        self.groups
+            .as_ref()?
+            .iter()
+            .find(|group| pred(group))
+            .cloned()
+    }
+}
+
+impl UserDb for FileUserDb {
+    fn user_by_name(&self, name: &str) -> Option<User> {
+        match self.users {
+            Some(_) => self.find_user(|user| user.name == name),
+            None => NssUserDb.user_by_name(name),
+        }
+    }
+
+    fn user_by_uid(&self, uid: uid_t) -> Option<User> {
+        match self.users {
+            Some(_) => self.find_user(|user| user.uid == uid),
+            None => NssUserDb.user_by_uid(uid),
+        }
+    }
+
+    fn group_by_name(&self, name: &str) -> Option<Group> {
+        match self.groups {
+            Some(_) => self.find_group(|group| group.name == name),
+            None => NssUserDb.group_by_name(name),
+        }
+    }
+
+    fn group_by_gid(&self, gid: gid_t) -> Option<Group> {
+        match self.groups {
+            Some(_) => self.find_group(|group| group.gid == gid),
+            None => NssUserDb.group_by_gid(gid),
+        }
+    }
+
+    fn group_list(&self, user: &User) -> Vec<gid_t> {
+        let groups = match &self.groups {
+            Some(groups) => groups,
+            None => return NssUserDb.group_list(user),
+        };
+        let mut list = vec![user.gid];
+        for group in groups {
+            if group.members.contains(&user.name) && !list.contains(&group.gid) {
+                list.push(group.gid);
+            }
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh\n\
+                          # comment\n\
+                          \n\
+                          alice:x:1000:1000\n\
+                          bad:x:uid:1\n\
+                          :x:5:5\n";
+    const GROUP: &str = "root:x:0:\n\
+                         users:x:100:alice,bob\n\
+                         wheel:x:10:alice\n\
+                         bad:x:gid:alice\n";
+
+    fn db() -> FileUserDb {
+        FileUserDb {
+            users: Some(FileUserDb::parse_passwd(PASSWD)),
+            groups: Some(FileUserDb::parse_group(GROUP)),
+        }
+    }
+
+    #[test]
+    fn parse_passwd_skips_invalid_lines() {
+        let users = FileUserDb::parse_passwd(PASSWD);
+        let users: Vec<(&str, uid_t, gid_t)> = users
+            .iter()
+            .map(|user| (user.name.as_str(), user.uid, user.gid))
+            .collect();
+        assert_eq!(users, [("root", 0, 0), ("alice", 1000, 1000)]);
+    }
+
+    #[test]
+    fn parse_group_members() {
+        let groups = FileUserDb::parse_group(GROUP);
+        assert_eq!(groups.len(), 3);
+        assert!(groups[0].members.is_empty());
+        assert_eq!(groups[1].members, ["alice", "bob"]);
+        assert_eq!(groups[2].gid, 10);
+    }
+
+    #[test]
+    fn lookups() {
+        let db = db();
+        assert_eq!(db.user_by_name("alice").map(|user| user.uid), Some(1000));
+        assert_eq!(
+            db.user_by_uid(0).map(|user| user.name),
+            Some(String::from("root"))
+        );
+        assert!(db.user_by_name("bob").is_none());
+        assert_eq!(db.group_by_name("wheel").map(|group| group.gid), Some(10));
+        assert!(db.group_by_gid(1000).is_none());
+    }
+
+    #[test]
+    fn group_list_starts_with_primary_group() {
+        let db = db();
+        let alice = db.user_by_name("alice").unwrap();
+        assert_eq!(db.group_list(&alice), [1000, 100, 10]);
+        let root = db.user_by_uid(0).unwrap();
+        assert_eq!(db.group_list(&root), [0]);
+    }
+}